@@ -0,0 +1,126 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+const K1: f32 = 1.2;
+const B: f32 = 0.75;
+
+/// Splits on non-alphanumeric characters and lowercases, then lightly stems
+/// each token so close word forms (e.g. "authentication"/"authenticate")
+/// collapse to the same term.
+fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|s| !s.is_empty())
+        .map(|s| stem(&s.to_lowercase()))
+        .collect()
+}
+
+/// A minimal suffix-stripping stemmer: not a real Porter stemmer, just enough
+/// to help BM25 recall on code/docs without pulling in a dependency for it.
+fn stem(word: &str) -> String {
+    for suffix in ["ational", "ation", "ate", "ing", "ed", "es", "s"] {
+        if word.len() > suffix.len() + 2 {
+            if let Some(stripped) = word.strip_suffix(suffix) {
+                return stripped.to_string();
+            }
+        }
+    }
+    word.to_string()
+}
+
+/// A classic BM25 ranked-retrieval index over a fixed set of documents.
+///
+/// `score(query)` ranks documents by, for each query term `t`:
+/// `idf(t) * (tf * (k1+1)) / (tf + k1*(1 - b + b*dl/avgdl))`, with
+/// `idf(t) = ln((N - df + 0.5)/(df + 0.5) + 1)`.
+pub(crate) struct Bm25Index {
+    /// term -> postings of (doc_id, term frequency in that doc)
+    postings: HashMap<String, Vec<(usize, usize)>>,
+    doc_lengths: Vec<usize>,
+    avg_doc_len: f32,
+    num_docs: usize,
+}
+
+impl Bm25Index {
+    pub(crate) fn build(documents: &[String]) -> Self {
+        let mut postings: HashMap<String, Vec<(usize, usize)>> = HashMap::new();
+        let mut doc_lengths = Vec::with_capacity(documents.len());
+
+        for (doc_id, document) in documents.iter().enumerate() {
+            let tokens = tokenize(document);
+            doc_lengths.push(tokens.len());
+
+            let mut term_freqs: HashMap<String, usize> = HashMap::new();
+            for token in tokens {
+                *term_freqs.entry(token).or_insert(0) += 1;
+            }
+            for (term, freq) in term_freqs {
+                postings.entry(term).or_default().push((doc_id, freq));
+            }
+        }
+
+        let num_docs = documents.len();
+        let avg_doc_len = if num_docs == 0 {
+            0.0
+        } else {
+            doc_lengths.iter().sum::<usize>() as f32 / num_docs as f32
+        };
+
+        Bm25Index {
+            postings,
+            doc_lengths,
+            avg_doc_len,
+            num_docs,
+        }
+    }
+
+    /// Scores every document containing at least one query term, returning
+    /// `(doc_id, score)` pairs ordered most relevant first.
+    pub(crate) fn score(&self, query: &str) -> Vec<(usize, f32)> {
+        let mut scores: HashMap<usize, f32> = HashMap::new();
+
+        for term in tokenize(query) {
+            let Some(postings) = self.postings.get(&term) else {
+                continue;
+            };
+
+            let df = postings.len() as f32;
+            let idf = ((self.num_docs as f32 - df + 0.5) / (df + 0.5) + 1.0).ln();
+
+            for &(doc_id, tf) in postings {
+                let tf = tf as f32;
+                let dl = self.doc_lengths[doc_id] as f32;
+                let denom = tf + K1 * (1.0 - B + B * dl / self.avg_doc_len.max(1.0));
+                *scores.entry(doc_id).or_insert(0.0) += idf * (tf * (K1 + 1.0)) / denom;
+            }
+        }
+
+        let mut scored: Vec<(usize, f32)> = scores.into_iter().collect();
+        scored.sort_by(|(_, a), (_, b)| b.partial_cmp(a).unwrap_or(Ordering::Equal));
+        scored
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Locks in the doc comment's own example: "authentication" and
+    /// "authenticate" must stem to the same term so a query using one form
+    /// ranks documents written with the other.
+    #[test]
+    fn stemming_collapses_authentication_and_authenticate() {
+        assert_eq!(stem("authentication"), stem("authenticate"));
+    }
+
+    #[test]
+    fn ranks_matching_document_above_unrelated_one() {
+        let documents = vec![
+            "the login flow checks the user's authentication token".to_string(),
+            "the garden needs watering twice a week".to_string(),
+        ];
+        let index = Bm25Index::build(&documents);
+
+        let scored = index.score("authenticate");
+        assert_eq!(scored.first().map(|(doc_id, _)| *doc_id), Some(0));
+    }
+}
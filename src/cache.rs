@@ -0,0 +1,139 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Returns a hex-encoded SHA-256 hash of `content`, used to detect when a
+/// cached embedding is stale because the underlying file changed.
+pub(crate) fn hash_content(content: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(content.as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CacheEntry {
+    path: PathBuf,
+    chunk_index: usize,
+    content_hash: String,
+    model: String,
+    embedding: Vec<f32>,
+}
+
+/// An on-disk cache of chunk embeddings, keyed by `(file path, chunk index,
+/// content hash, model)`. Backed by a plain JSON-lines file (rewritten in
+/// full on `flush`) rather than sqlite, to keep this dependency-light like
+/// the rest of the crate.
+///
+/// Entries are invalidated lazily: a lookup whose content hash no longer
+/// matches what's on disk is dropped right there rather than eagerly swept,
+/// similar to how zoxide purges stale entries as it encounters them.
+pub(crate) struct EmbeddingCache {
+    cache_path: PathBuf,
+    entries: HashMap<(PathBuf, usize, String), CacheEntry>,
+    dirty: bool,
+}
+
+impl EmbeddingCache {
+    /// Loads the cache file under the OS cache dir, e.g.
+    /// `~/.cache/llmgrep/embeddings.jsonl` on Linux, creating the directory
+    /// if needed. A missing file starts an empty cache; the file itself is
+    /// first written by `flush`.
+    pub(crate) fn open() -> Result<Self> {
+        let cache_path = Self::default_path()?;
+        let mut entries = HashMap::new();
+
+        if let Ok(contents) = fs::read_to_string(&cache_path) {
+            for line in contents.lines() {
+                if let Ok(entry) = serde_json::from_str::<CacheEntry>(line) {
+                    entries.insert(
+                        (entry.path.clone(), entry.chunk_index, entry.model.clone()),
+                        entry,
+                    );
+                }
+            }
+        }
+
+        Ok(EmbeddingCache {
+            cache_path,
+            entries,
+            dirty: false,
+        })
+    }
+
+    fn default_path() -> Result<PathBuf> {
+        let dir = dirs::cache_dir()
+            .ok_or_else(|| anyhow::anyhow!("could not determine a cache directory for llmgrep"))?
+            .join("llmgrep");
+        fs::create_dir_all(&dir)?;
+        Ok(dir.join("embeddings.jsonl"))
+    }
+
+    /// Returns the cached embedding for `(path, chunk_index)`, provided the
+    /// content hash and model still match. A stale entry (content changed
+    /// since it was cached) is dropped so it doesn't linger on the next flush.
+    pub(crate) fn get(
+        &mut self,
+        path: &Path,
+        chunk_index: usize,
+        content_hash: &str,
+        model: &str,
+    ) -> Option<Vec<f32>> {
+        let key = (path.to_path_buf(), chunk_index, model.to_string());
+        match self.entries.get(&key) {
+            Some(entry) if entry.content_hash == content_hash => Some(entry.embedding.clone()),
+            Some(_) => {
+                self.entries.remove(&key);
+                self.dirty = true;
+                None
+            }
+            None => None,
+        }
+    }
+
+    pub(crate) fn put(
+        &mut self,
+        path: PathBuf,
+        chunk_index: usize,
+        content_hash: String,
+        model: String,
+        embedding: Vec<f32>,
+    ) {
+        self.entries.insert(
+            (path.clone(), chunk_index, model.clone()),
+            CacheEntry {
+                path,
+                chunk_index,
+                content_hash,
+                model,
+                embedding,
+            },
+        );
+        self.dirty = true;
+    }
+
+    /// Persists the cache to disk if anything changed, rewriting the whole
+    /// file so entries dropped by `get` don't linger.
+    pub(crate) fn flush(&mut self) -> Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&self.cache_path)?;
+
+        for entry in self.entries.values() {
+            writeln!(file, "{}", serde_json::to_string(entry)?)?;
+        }
+
+        self.dirty = false;
+        Ok(())
+    }
+}
@@ -0,0 +1,11 @@
+/// Splits `content` into chunks of at most `chunk_size` characters each, so
+/// large files can be fed to the LLM/embedding model in pieces that fit its
+/// context window.
+pub(crate) fn chunk_content(content: &str, chunk_size: usize) -> Vec<String> {
+    content
+        .chars()
+        .collect::<Vec<char>>()
+        .chunks(chunk_size)
+        .map(|chunk| chunk.iter().collect())
+        .collect()
+}
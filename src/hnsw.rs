@@ -0,0 +1,291 @@
+use rand::Rng;
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashSet};
+
+/// Cosine similarity between two equal-length vectors: `(a·b)/(‖a‖‖b‖)`.
+pub(crate) fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm_a = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norm_b = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+
+    if norm_a == 0.0 || norm_b == 0.0 {
+        0.0
+    } else {
+        dot / (norm_a * norm_b)
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Candidate {
+    id: usize,
+    score: f32,
+}
+
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Self) -> bool {
+        self.score == other.score
+    }
+}
+impl Eq for Candidate {}
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.score.partial_cmp(&other.score).unwrap_or(Ordering::Equal)
+    }
+}
+
+/// An in-memory HNSW (Hierarchical Navigable Small World) graph over
+/// cosine-similarity vectors.
+///
+/// Search starts at the entry point in the top (sparsest) layer, greedily
+/// hops to the neighbor closest to the query, and descends a layer once no
+/// closer neighbor exists, finally running a beam search of width `ef` over
+/// layer 0 to collect the `k` nearest vectors. This scales far better than
+/// brute-force cosine comparison once the number of embedded chunks grows
+/// past a few thousand.
+pub(crate) struct HnswIndex {
+    vectors: Vec<Vec<f32>>,
+    /// `layers[level][node]` holds the neighbor ids of `node` at `level`.
+    layers: Vec<Vec<Vec<usize>>>,
+    entry_point: Option<usize>,
+    m: usize,
+    ef_construction: usize,
+    level_norm: f64,
+}
+
+impl HnswIndex {
+    /// `m` is the max number of neighbors kept per node per layer; `ef_construction`
+    /// is the beam width used while inserting (higher = better recall, slower builds).
+    pub(crate) fn new(m: usize, ef_construction: usize) -> Self {
+        HnswIndex {
+            vectors: Vec::new(),
+            layers: Vec::new(),
+            entry_point: None,
+            m,
+            ef_construction,
+            level_norm: 1.0 / (m.max(2) as f64).ln(),
+        }
+    }
+
+    fn random_level(&self) -> usize {
+        let uniform: f64 = rand::thread_rng().gen_range(f64::EPSILON..1.0);
+        (-uniform.ln() * self.level_norm).floor() as usize
+    }
+
+    /// Inserts `vector` into the graph and returns its id (its index into
+    /// insertion order, used to map back to the caller's own bookkeeping).
+    pub(crate) fn insert(&mut self, vector: Vec<f32>) -> usize {
+        let id = self.vectors.len();
+        let level = self.random_level();
+        self.vectors.push(vector);
+
+        // Captured before `self.layers` grows to cover `level`, so promoting
+        // the entry point below actually compares against the graph's
+        // previous top layer instead of one that was just stretched to fit.
+        let top_level_before_insert = self.layers.len().checked_sub(1);
+
+        while self.layers.len() <= level {
+            self.layers.push(Vec::new());
+        }
+        for layer in &mut self.layers {
+            while layer.len() <= id {
+                layer.push(Vec::new());
+            }
+        }
+
+        let Some(entry_point) = self.entry_point else {
+            self.entry_point = Some(id);
+            return id;
+        };
+
+        let query = self.vectors[id].clone();
+        let top_level = self.layers.len() - 1;
+        let mut current = entry_point;
+
+        // Descend from the top layer down to just above our level, always
+        // stepping to whichever neighbor is closest to the new vector.
+        for layer_idx in (level + 1..=top_level).rev() {
+            current = self.greedy_closest(current, &query, layer_idx);
+        }
+
+        // From our level down to layer 0, connect to the `m` nearest
+        // neighbors found via a beam search of width `ef_construction`.
+        for layer_idx in (0..=level.min(top_level)).rev() {
+            let nearest = self.search_layer(current, &query, self.ef_construction, layer_idx);
+            let neighbors: Vec<usize> = nearest.into_iter().take(self.m).map(|c| c.id).collect();
+
+            for &neighbor in &neighbors {
+                self.layers[layer_idx][id].push(neighbor);
+                self.layers[layer_idx][neighbor].push(id);
+            }
+            if let Some(&closest) = neighbors.first() {
+                current = closest;
+            }
+        }
+
+        let promoted = match top_level_before_insert {
+            Some(previous_top) => level > previous_top,
+            None => true,
+        };
+        if promoted {
+            self.entry_point = Some(id);
+        }
+
+        id
+    }
+
+    fn greedy_closest(&self, start: usize, query: &[f32], layer_idx: usize) -> usize {
+        let mut current = start;
+        let mut current_score = cosine_similarity(&self.vectors[current], query);
+
+        loop {
+            let mut improved = false;
+            for &neighbor in &self.layers[layer_idx][current] {
+                let score = cosine_similarity(&self.vectors[neighbor], query);
+                if score > current_score {
+                    current = neighbor;
+                    current_score = score;
+                    improved = true;
+                }
+            }
+            if !improved {
+                return current;
+            }
+        }
+    }
+
+    /// Beam search of width `ef` over a single layer, returning candidates
+    /// ordered by descending cosine similarity.
+    fn search_layer(&self, entry: usize, query: &[f32], ef: usize, layer_idx: usize) -> Vec<Candidate> {
+        let mut visited: HashSet<usize> = HashSet::new();
+        visited.insert(entry);
+
+        let entry_candidate = Candidate {
+            id: entry,
+            score: cosine_similarity(&self.vectors[entry], query),
+        };
+        let mut frontier: BinaryHeap<Candidate> = BinaryHeap::new();
+        frontier.push(entry_candidate);
+        let mut best: Vec<Candidate> = vec![entry_candidate];
+
+        while let Some(current) = frontier.pop() {
+            let worst_best = best.iter().map(|c| c.score).fold(f32::INFINITY, f32::min);
+            if best.len() >= ef && current.score < worst_best {
+                break;
+            }
+
+            for &neighbor in &self.layers[layer_idx][current.id] {
+                if visited.insert(neighbor) {
+                    let candidate = Candidate {
+                        id: neighbor,
+                        score: cosine_similarity(&self.vectors[neighbor], query),
+                    };
+                    frontier.push(candidate);
+                    best.push(candidate);
+
+                    // Keep `best` bounded to `ef` so `worst_best` above
+                    // actually tightens as better candidates are found,
+                    // instead of only ever growing until the loop ends.
+                    if best.len() > ef {
+                        if let Some((worst_idx, _)) = best
+                            .iter()
+                            .enumerate()
+                            .min_by(|(_, a), (_, b)| a.score.partial_cmp(&b.score).unwrap_or(Ordering::Equal))
+                        {
+                            best.remove(worst_idx);
+                        }
+                    }
+                }
+            }
+        }
+
+        best.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(Ordering::Equal));
+        best.truncate(ef);
+        best
+    }
+
+    /// Returns up to `k` nearest vectors to `query` as `(id, cosine_similarity)`
+    /// pairs, ordered by descending similarity.
+    pub(crate) fn search(&self, query: &[f32], k: usize, ef: usize) -> Vec<(usize, f32)> {
+        let Some(entry_point) = self.entry_point else {
+            return Vec::new();
+        };
+
+        let top_level = self.layers.len() - 1;
+        let mut current = entry_point;
+        for layer_idx in (1..=top_level).rev() {
+            current = self.greedy_closest(current, query, layer_idx);
+        }
+
+        self.search_layer(current, query, ef.max(k), 0)
+            .into_iter()
+            .take(k)
+            .map(|c| (c.id, c.score))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Deterministic pseudo-random vector with components in `[0, 1]`, so the
+    /// test data is the same on every run (the graph's layer assignment in
+    /// `insert` still comes from `rand`'s thread RNG).
+    fn vector(seed: usize, dims: usize) -> Vec<f32> {
+        (0..dims)
+            .map(|d| {
+                let x = seed
+                    .wrapping_mul(2654435761)
+                    .wrapping_add(d.wrapping_mul(40503)) as f32;
+                (x.sin() + 1.0) / 2.0
+            })
+            .collect()
+    }
+
+    fn brute_force_nearest(vectors: &[Vec<f32>], query: &[f32], k: usize) -> Vec<usize> {
+        let mut scored: Vec<(usize, f32)> = vectors
+            .iter()
+            .enumerate()
+            .map(|(id, v)| (id, cosine_similarity(v, query)))
+            .collect();
+        scored.sort_by(|(_, a), (_, b)| b.partial_cmp(a).unwrap_or(Ordering::Equal));
+        scored.into_iter().take(k).map(|(id, _)| id).collect()
+    }
+
+    /// HNSW search should agree with brute-force cosine similarity on most of
+    /// its top-k results given a generous `ef`; this also exercises
+    /// `insert`'s entry-point promotion and `search_layer`'s beam across
+    /// enough nodes (200) to span several layers.
+    #[test]
+    fn search_recalls_most_brute_force_nearest_neighbors() {
+        const DIMS: usize = 16;
+        const NUM_VECTORS: usize = 200;
+        const K: usize = 10;
+
+        let vectors: Vec<Vec<f32>> = (0..NUM_VECTORS).map(|i| vector(i, DIMS)).collect();
+
+        let mut index = HnswIndex::new(16, 100);
+        for v in &vectors {
+            index.insert(v.clone());
+        }
+
+        let query = vector(NUM_VECTORS + 1, DIMS);
+        let expected = brute_force_nearest(&vectors, &query, K);
+        let found: Vec<usize> = index
+            .search(&query, K, 64)
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect();
+
+        let overlap = found.iter().filter(|id| expected.contains(id)).count();
+        assert!(
+            overlap * 2 >= K,
+            "expected at least half of the top-{K} brute-force neighbors, found {overlap}: {found:?} vs {expected:?}"
+        );
+    }
+}
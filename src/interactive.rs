@@ -0,0 +1,121 @@
+use anyhow::{Context, Result};
+use fuzzy_matcher::skim::SkimMatcherV2;
+use fuzzy_matcher::FuzzyMatcher;
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+/// Narrows `candidates` down to the files the user actually wants analyzed,
+/// by spawning `fzf` if it's on `PATH`, or falling back to a small built-in
+/// fuzzy-matcher prompt otherwise. Returns the selection in the same
+/// `(path, filename_score)` shape the caller passed in; when `print_score_only`
+/// is set, prints just the score of each selected file (handy for scripting).
+pub(crate) fn pick_candidates(
+    candidates: &[(PathBuf, f32)],
+    print_score_only: bool,
+) -> Result<Vec<(PathBuf, f32)>> {
+    if candidates.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let lines: Vec<String> = candidates
+        .iter()
+        .map(|(path, score)| format!("{:.2}\t{}", score, path.display()))
+        .collect();
+
+    let selected_lines = match pick_with_fzf(&lines)? {
+        Some(selected) => selected,
+        None => pick_with_builtin_fuzzy_matcher(&lines)?,
+    };
+
+    let selected: Vec<(PathBuf, f32)> = selected_lines
+        .iter()
+        .filter_map(|line| line.split_once('\t'))
+        .filter_map(|(_, path_str)| {
+            candidates
+                .iter()
+                .find(|(path, _)| path.display().to_string() == path_str)
+                .cloned()
+        })
+        .collect();
+
+    if print_score_only {
+        for (_, score) in &selected {
+            println!("{:.2}", score);
+        }
+    }
+
+    Ok(selected)
+}
+
+/// Spawns `fzf --multi`, piping `lines` to its stdin, and returns the
+/// selected lines. Returns `Ok(None)` (not an error) when `fzf` isn't
+/// installed, so the caller can fall back to the built-in matcher.
+fn pick_with_fzf(lines: &[String]) -> Result<Option<Vec<String>>> {
+    let mut child = match Command::new("fzf")
+        .arg("--multi")
+        .arg("--with-nth=2..") // show and search only the path; the score column is hidden
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(_) => return Ok(None),
+    };
+
+    {
+        let stdin = child.stdin.as_mut().context("fzf stdin was not piped")?;
+        stdin.write_all(lines.join("\n").as_bytes())?;
+    }
+
+    let output = child.wait_with_output()?;
+    let selected = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(|line| line.to_string())
+        .collect();
+    Ok(Some(selected))
+}
+
+/// A minimal interactive fallback for when `fzf` isn't on `PATH`: prompts for
+/// a query, fuzzy-matches it against every line with the same matcher `skim`
+/// is built on, and lets the user pick one or more of the ranked results.
+fn pick_with_builtin_fuzzy_matcher(lines: &[String]) -> Result<Vec<String>> {
+    print!("fzf not found; filter query (blank to list all): ");
+    io::stdout().flush()?;
+
+    let mut query = String::new();
+    io::stdin().lock().read_line(&mut query)?;
+    let query = query.trim();
+
+    let matcher = SkimMatcherV2::default();
+    let mut ranked: Vec<(i64, &String)> = lines
+        .iter()
+        .filter_map(|line| {
+            if query.is_empty() {
+                Some((0, line))
+            } else {
+                matcher.fuzzy_match(line, query).map(|score| (score, line))
+            }
+        })
+        .collect();
+    ranked.sort_by_key(|&(score, _)| std::cmp::Reverse(score));
+
+    for (i, (_, line)) in ranked.iter().enumerate() {
+        println!("{}: {}", i + 1, line);
+    }
+
+    print!("Select one or more (comma-separated numbers, blank for none): ");
+    io::stdout().flush()?;
+    let mut selection = String::new();
+    io::stdin().lock().read_line(&mut selection)?;
+
+    let chosen = selection
+        .trim()
+        .split(',')
+        .filter_map(|token| token.trim().parse::<usize>().ok())
+        .filter_map(|index| ranked.get(index.checked_sub(1)?))
+        .map(|(_, line)| (*line).clone())
+        .collect();
+
+    Ok(chosen)
+}
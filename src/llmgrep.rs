@@ -1,13 +1,24 @@
+use crate::chunk::chunk_content;
+use crate::interactive::pick_candidates;
 use crate::llmsort::LlmSort;
 use anyhow::Result;
+use async_stream::stream;
+use futures_core::stream::Stream;
+use futures_util::pin_mut;
+use futures_util::stream::{self, StreamExt};
 use ollama_rs::generation::completion::request::GenerationRequest;
 use ollama_rs::generation::parameters::FormatType;
 use ollama_rs::Ollama;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::fs;
 use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
 
 const CHUNK_SIZE: usize = 2000; // Characters per chunk
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+const MAX_REQUEST_RETRIES: usize = 2;
 
 #[derive(Debug, Deserialize)]
 struct AnalysisResponse {
@@ -15,20 +26,60 @@ struct AnalysisResponse {
     analysis: Option<String>,
 }
 
+/// One semantic match found by `analyze_content`.
+#[derive(Debug, Clone, Serialize)]
+pub struct SearchResult {
+    pub path: PathBuf,
+    pub filename_score: f32,
+    pub analysis: String,
+    pub chunk_start: usize,
+}
+
+/// How search results should be surfaced on stdout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// Human-readable progress and match lines (the original behavior).
+    Text,
+    /// One `SearchResult` JSON object per line, printed in candidate order
+    /// once the content-analysis pass finishes.
+    Json,
+}
+
+/// The CLI flags that shape how a search is narrowed and presented, bundled
+/// up so `search_directory_stream`/`search_directory` don't accumulate one
+/// parameter per flag.
+#[derive(Debug, Clone, Copy)]
+pub struct SearchOptions {
+    pub format: OutputFormat,
+    /// Narrow the sorted candidates down interactively before content analysis.
+    pub interactive: bool,
+    /// With `interactive`, print only the selected candidates' scores and
+    /// skip the content-analysis pass entirely.
+    pub print_score_only: bool,
+}
+
 pub struct LlmGrep {
     ollama: Ollama,
     model: String,
     sorter: LlmSort,
+    /// Max number of content-analysis requests dispatched to Ollama concurrently.
+    concurrency: usize,
 }
 
 impl LlmGrep {
-    pub async fn new(model: &str) -> Result<Self> {
+    pub async fn new(
+        model: &str,
+        verbose: bool,
+        prefilter_top_n: Option<usize>,
+        concurrency: usize,
+    ) -> Result<Self> {
         let ollama = Ollama::default();
-        let sorter = LlmSort::new(model).await?;
+        let sorter = LlmSort::new(model, verbose, prefilter_top_n, concurrency).await?;
         Ok(LlmGrep {
             ollama,
             model: model.to_string(),
             sorter,
+            concurrency: concurrency.max(1),
         })
     }
 
@@ -60,7 +111,7 @@ Remember: Be concise, objective, and focus on semantic relevance rather than sur
         let prompt = format!(
             "
             Filename: {}
-            Text: 
+            Text:
             {}\n
             Does the user query '{}' relate to the above text? \
             Respond with a JSON object containing has_match and analysis fields.",
@@ -83,70 +134,180 @@ Remember: Be concise, objective, and focus on semantic relevance rather than sur
         }
     }
 
-    pub async fn search_directory(
+    /// `analyze_content`, but bounded by `REQUEST_TIMEOUT` and retried up to
+    /// `MAX_REQUEST_RETRIES` times, so one slow or hung Ollama generation
+    /// doesn't stall the whole run.
+    async fn analyze_content_with_retry(
         &self,
-        dir: &Path,
-        ignore_paths: &[&str],
+        path: &Path,
+        content: &str,
         query: &str,
-    ) -> Result<()> {
-        println!("First pass: Recursively collecting and scoring all files...");
-
-        let mut try_count = 0;
-        let mut candidates = Vec::new();
-        // Pass ignore_paths to collect_and_sort_candidates
-        while try_count < 3 {
-            candidates = self
-                .sorter
-                .collect_and_sort_candidates(dir, ignore_paths, query)
-                .await?;
-
-            if candidates.is_empty() {
-                println!("No candidates found. Exiting...");
-                return Ok(());
+    ) -> Result<Option<String>> {
+        let mut last_err = None;
+
+        for _attempt in 0..=MAX_REQUEST_RETRIES {
+            match tokio::time::timeout(REQUEST_TIMEOUT, self.analyze_content(path, content, query))
+                .await
+            {
+                Ok(Ok(analysis)) => return Ok(analysis),
+                Ok(Err(e)) => last_err = Some(e),
+                Err(_) => {
+                    last_err = Some(anyhow::anyhow!(
+                        "content analysis of {} timed out after {:?}",
+                        path.display(),
+                        REQUEST_TIMEOUT
+                    ))
+                }
+            }
+        }
+
+        Err(last_err.unwrap_or_else(|| anyhow::anyhow!("content analysis failed")))
+    }
+
+    /// Reads `path` and walks its chunks until one matches `query`, returning
+    /// the first match (or `None`). Used as the per-file unit of work that
+    /// `search_directory_stream` fans out across its concurrency limit.
+    async fn analyze_file(
+        &self,
+        path: PathBuf,
+        filename_score: f32,
+        query: &str,
+        cancel: &CancellationToken,
+    ) -> Option<SearchResult> {
+        let content = fs::read(&path).ok()?;
+        // Convert to string (we know it's valid UTF-8 from pre-filtering)
+        let content_str = String::from_utf8_lossy(&content);
+
+        for (chunk_index, chunk_str) in chunk_content(&content_str, CHUNK_SIZE).into_iter().enumerate() {
+            if cancel.is_cancelled() {
+                return None;
             }
 
-            if candidates.iter().any(|(_, score)| *score > 0.0) {
-                break;
+            if let Ok(Some(analysis)) = self.analyze_content_with_retry(&path, &chunk_str, query).await {
+                return Some(SearchResult {
+                    path,
+                    filename_score,
+                    analysis,
+                    chunk_start: chunk_index * CHUNK_SIZE,
+                });
             }
-            try_count += 1;
         }
-        println!(
-            "Sorted candidates: \n{}",
-            candidates
-                .iter()
-                .map(|(path, score)| format!("{} (score: {:.2})", path.display(), score))
-                .collect::<Vec<String>>()
-                .join("\n")
-        );
 
-        println!("\nSecond pass: analyzing content of promising candidates...");
-
-        // Second pass: analyze content of promising candidates
-        for (path, score) in candidates {
-            let content = match fs::read(&path) {
-                Ok(content) => content,
-                Err(_) => continue,
-            };
-
-            println!(
-                "Analyzing content of {} (filename score: {:.2})",
-                path.display(),
-                score
-            );
-
-            // Convert to string (we know it's valid UTF-8 from pre-filtering)
-            let content_str = String::from_utf8_lossy(&content);
-
-            // Process file in chunks if necessary
-            for chunk in content_str
-                .chars()
-                .collect::<Vec<char>>()
-                .chunks(CHUNK_SIZE)
-            {
-                let chunk_str: String = chunk.iter().collect();
-                if let Ok(Some(relevance)) = self.analyze_content(&path, &chunk_str, query).await {
-                    println!("{}: {}", path.display(), relevance);
-                    break; // Stop processing chunks once we find a match
+        None
+    }
+
+    /// Library entry point: searches `dirs` for `query` and yields each
+    /// `SearchResult` once the content-analysis pass finishes, in candidate
+    /// order (not completion order). `cancel` is checked between batches and
+    /// chunks so a caller can abort an in-flight search cleanly.
+    pub fn search_directory_stream<'a>(
+        &'a self,
+        dirs: &'a [PathBuf],
+        include_paths: &'a [&'a str],
+        exclude_paths: &'a [&'a str],
+        query: &'a str,
+        options: SearchOptions,
+        cancel: CancellationToken,
+    ) -> impl Stream<Item = SearchResult> + 'a {
+        stream! {
+            let mut try_count = 0;
+            let mut candidates = Vec::new();
+            while try_count < 3 {
+                if cancel.is_cancelled() {
+                    return;
+                }
+
+                candidates = match self
+                    .sorter
+                    .collect_and_sort_candidates(dirs, include_paths, exclude_paths, query, &cancel)
+                    .await
+                {
+                    Ok(candidates) => candidates,
+                    Err(_) => return,
+                };
+
+                if candidates.is_empty() {
+                    return;
+                }
+
+                if candidates.iter().any(|(_, score)| *score > 0.0) {
+                    break;
+                }
+                try_count += 1;
+            }
+
+            if options.interactive {
+                candidates = match pick_candidates(&candidates, options.print_score_only) {
+                    Ok(selected) => selected,
+                    Err(_) => return,
+                };
+            }
+
+            // `print_score_only` is a scripting aid: stdout should contain
+            // just the scores `pick_candidates` already printed, not the
+            // content-analysis results below.
+            if options.print_score_only {
+                return;
+            }
+
+            // Analyze up to `self.concurrency` files at once; results finish
+            // in whatever order their analysis completes, so they're tagged
+            // with their candidate index and sorted back into candidate
+            // order before being yielded, the same way the embedding pass
+            // sorts its batch results back into order.
+            let mut analyses: Vec<(usize, Option<SearchResult>)> = stream::iter(candidates.into_iter().enumerate())
+                .map(|(index, (path, filename_score))| {
+                    let cancel = cancel.clone();
+                    async move { (index, self.analyze_file(path, filename_score, query, &cancel).await) }
+                })
+                .buffer_unordered(self.concurrency)
+                .collect()
+                .await;
+
+            if cancel.is_cancelled() {
+                return;
+            }
+
+            analyses.sort_by_key(|(index, _)| *index);
+
+            for (_, result) in analyses {
+                if let Some(search_result) = result {
+                    yield search_result;
+                }
+            }
+        }
+    }
+
+    /// CLI entry point: drives `search_directory_stream` to completion (or
+    /// cancellation), printing each result in the requested `format`.
+    pub async fn search_directory(
+        &self,
+        dirs: &[PathBuf],
+        include_paths: &[&str],
+        exclude_paths: &[&str],
+        query: &str,
+        options: SearchOptions,
+        cancel: CancellationToken,
+    ) -> Result<()> {
+        if options.format == OutputFormat::Text {
+            println!("First pass: Recursively collecting and scoring all files, analyzing content of promising candidates...");
+        }
+
+        let results = self.search_directory_stream(dirs, include_paths, exclude_paths, query, options, cancel);
+        pin_mut!(results);
+
+        while let Some(result) = results.next().await {
+            match options.format {
+                OutputFormat::Text => {
+                    println!(
+                        "{} (filename score: {:.2}): {}",
+                        result.path.display(),
+                        result.filename_score,
+                        result.analysis
+                    );
+                }
+                OutputFormat::Json => {
+                    println!("{}", serde_json::to_string(&result)?);
                 }
             }
         }
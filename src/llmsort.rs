@@ -1,160 +1,324 @@
+use crate::bm25::Bm25Index;
+use crate::cache::{hash_content, EmbeddingCache};
+use crate::chunk::chunk_content;
+use crate::hnsw::HnswIndex;
 use anyhow::Result;
-use ollama_rs::generation::completion::request::GenerationRequest;
-use ollama_rs::generation::parameters::FormatType;
+use futures_util::stream::{self, StreamExt};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use ollama_rs::generation::embeddings::request::{EmbeddingsInput, GenerateEmbeddingsRequest};
 use ollama_rs::Ollama;
-use serde::Deserialize;
+use std::collections::HashMap;
 use std::fs;
 use std::path::Path;
 use std::path::PathBuf;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
 
 const MAX_FILE_SIZE: u64 = 1024 * 1024; // 1MB
 const BATCH_SIZE: usize = 100;
 const MIN_BINARY_CHECK_SIZE: usize = 1000;
 const BINARY_THRESHOLD: usize = 300; // 30% of MIN_BINARY_CHECK_SIZE
 const MAX_SORT_TRY_COUNT: usize = 3;
-
-#[derive(Debug, Deserialize)]
-struct FileScores {
-    filenames: Vec<FileScore>,
-}
-
-#[derive(Debug, Deserialize)]
-struct FileScore {
-    filename: String,
-    score: f32,
-}
+const EMBED_CHUNK_SIZE: usize = 2000; // Characters per chunk, matches LlmGrep's content chunking
+const EMBEDDING_SEARCH_K: usize = 50; // Max files returned from the embedding retrieval pass
+const HNSW_M: usize = 16; // Max neighbors per node per layer
+const HNSW_EF_CONSTRUCTION: usize = 100; // Beam width used while building the graph
+const HNSW_EF_SEARCH: usize = 64; // Beam width used while querying the graph
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+const MAX_REQUEST_RETRIES: usize = 2;
 
 pub struct LlmSort {
     ollama: Ollama,
     model: String,
     verbose: bool,
+    /// When set, `collect_and_sort_candidates` keeps only the top-N candidates
+    /// by BM25 lexical score before the (much pricier) embedding pass.
+    prefilter_top_n: Option<usize>,
+    /// Max number of embedding requests dispatched to Ollama concurrently.
+    concurrency: usize,
+}
+
+/// Builds a `GlobSet` from plain glob patterns, also matching the pattern as a
+/// directory prefix (e.g. `src` additionally matches everything under `src/`)
+/// so callers get "starts with" semantics on top of full glob matching.
+///
+/// Patterns are also matched at any depth, not just rooted at the search
+/// root: a bare literal like `target` or `node_modules` matches that name
+/// wherever it appears in the tree (e.g. `crate-b/target`,
+/// `packages/foo/node_modules`), the same way the old prefix-based
+/// `should_ignore` did before it was rewritten to use globs.
+fn build_globset(patterns: &[&str]) -> Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(Glob::new(pattern)?);
+        builder.add(Glob::new(&format!("{pattern}/**"))?);
+        builder.add(Glob::new(&format!("**/{pattern}"))?);
+        builder.add(Glob::new(&format!("**/{pattern}/**"))?);
+    }
+    Ok(builder.build()?)
 }
 
 impl LlmSort {
-    pub async fn new(model: &str, verbose: bool) -> Result<Self> {
+    pub async fn new(
+        model: &str,
+        verbose: bool,
+        prefilter_top_n: Option<usize>,
+        concurrency: usize,
+    ) -> Result<Self> {
         let ollama = Ollama::default();
         Ok(LlmSort {
             ollama,
             model: model.to_string(),
             verbose,
+            prefilter_top_n,
+            concurrency: concurrency.max(1),
         })
     }
 
-    async fn analyze_filenames_batch(
+    /// Ranks `candidates` by BM25 lexical score against `query` and keeps only
+    /// the top `top_n`, so the embedding pass only has to look at files that
+    /// already have some lexical signal.
+    fn prefilter_with_bm25(&self, candidates: Vec<PathBuf>, query: &str, top_n: usize) -> Vec<PathBuf> {
+        let contents: Vec<String> = candidates
+            .iter()
+            .map(|path| fs::read_to_string(path).unwrap_or_default())
+            .collect();
+
+        let index = Bm25Index::build(&contents);
+        let ranked = index.score(query);
+
+        if self.verbose {
+            println!(
+                "BM25 prefilter: keeping top {} of {} candidates with lexical signal",
+                top_n.min(ranked.len()),
+                candidates.len()
+            );
+        }
+
+        ranked
+            .into_iter()
+            .take(top_n)
+            .map(|(doc_id, _)| candidates[doc_id].clone())
+            .collect()
+    }
+
+    /// Embeds a batch of texts in a single Ollama request, in the same order
+    /// they were passed in.
+    async fn embed_batch(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        let request = GenerateEmbeddingsRequest::new(
+            self.model.clone(),
+            EmbeddingsInput::Multiple(texts.to_vec()),
+        );
+        let response = self.ollama.generate_embeddings(request).await?;
+        Ok(response.embeddings)
+    }
+
+    /// `embed_batch`, but bounded by `REQUEST_TIMEOUT` and retried up to
+    /// `MAX_REQUEST_RETRIES` times, so one slow or hung Ollama generation
+    /// doesn't stall the whole run.
+    async fn embed_batch_with_retry(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        let mut last_err = None;
+
+        for attempt in 0..=MAX_REQUEST_RETRIES {
+            match tokio::time::timeout(REQUEST_TIMEOUT, self.embed_batch(texts)).await {
+                Ok(Ok(embeddings)) => return Ok(embeddings),
+                Ok(Err(e)) => last_err = Some(e),
+                Err(_) => {
+                    last_err = Some(anyhow::anyhow!(
+                        "embedding request timed out after {:?}",
+                        REQUEST_TIMEOUT
+                    ))
+                }
+            }
+
+            if self.verbose && attempt < MAX_REQUEST_RETRIES {
+                eprintln!(
+                    "Embedding batch failed (attempt {}/{}), retrying: {}",
+                    attempt + 1,
+                    MAX_REQUEST_RETRIES + 1,
+                    last_err.as_ref().unwrap()
+                );
+            }
+        }
+
+        Err(last_err.unwrap_or_else(|| anyhow::anyhow!("embedding batch failed")))
+    }
+
+    /// Cheap, keyword-free first pass: chunk every candidate's content, embed
+    /// the chunks and the query, and rank files by the cosine similarity of
+    /// their best-matching chunk. Chunks are indexed in an `HnswIndex` so this
+    /// scales well past a few thousand files, instead of the naive LLM
+    /// filename-scoring this replaces.
+    async fn score_candidates_by_embedding(
         &self,
-        files: &[(PathBuf, String)],
+        candidates: &[PathBuf],
         query: &str,
-    ) -> Result<Vec<f32>> {
-        let filenames: Vec<_> = files.iter().map(|(_, name)| name).collect();
-
-        let system_prompt = "You are a highly accurate filename analysis tool. Your task is to analyze filenames and estimate the probability they contain content matching a search query.
-
-Instructions:
-1. Evaluate each filename considering:
-   - Naming conventions and semantics
-   - File extensions and their typical content
-   - Common code/documentation patterns
-   - Word matches and related concepts
-2. Assign a score from 0.0 (irrelevant) to 1.0 (highly relevant)
-3. Return ONLY a valid JSON array of objects with 'filename' and 'score' fields, nothing else
-
-Example:
-Input: ['main.rs', 'auth.rs'] with query 'authentication'
-Output: [{\"filename\":\"main.rs\",\"score\":0.3},{\"filename\":\"auth.rs\",\"score\":0.9}]";
-
-        let prompt = format!(
-            "Analyze these filenames: {:#?}\nQuery: '{}'\n\nRespond with ONLY a JSON array. Example format: [{{\"filename\":\"example.rs\",\"score\":0.5}}]",
-            filenames, query
-        );
+        cancel: &CancellationToken,
+    ) -> Result<Vec<(PathBuf, f32)>> {
+        if cancel.is_cancelled() {
+            return Ok(Vec::new());
+        }
 
-        let mut request = GenerationRequest::new(self.model.clone(), prompt);
-        request.system = Some(system_prompt.to_string());
-        request.format = Some(FormatType::Json);
-
-        let response = self.ollama.generate(request).await?;
-
-        let scores: Vec<FileScore> = match serde_json::from_str::<FileScores>(&response.response) {
-            Ok(scores) => scores.filenames,
-            Err(e) => {
-                if self.verbose {
-                    eprintln!(
-                        "JSON parsing error: {}. Response was: {}",
-                        e,
-                        response.response.trim()
-                    );
+        let mut cache = EmbeddingCache::open()?;
+
+        let mut chunk_owners: Vec<PathBuf> = Vec::new();
+        let mut chunk_embeddings: Vec<Option<Vec<f32>>> = Vec::new();
+        let mut chunk_keys: Vec<(PathBuf, usize, String)> = Vec::new();
+        let mut pending_indices: Vec<usize> = Vec::new();
+        let mut pending_texts: Vec<String> = Vec::new();
+
+        for path in candidates {
+            let content = match fs::read_to_string(path) {
+                Ok(content) => content,
+                Err(_) => continue,
+            };
+            let content_hash = hash_content(&content);
+
+            for (chunk_index, chunk) in chunk_content(&content, EMBED_CHUNK_SIZE).into_iter().enumerate() {
+                if chunk.trim().is_empty() {
+                    continue;
                 }
-                // Try parsing again with a different format
-                match serde_json::from_str::<Vec<FileScore>>(&response.response) {
-                    Ok(scores) => scores,
-                    Err(e2) => {
-                        if self.verbose {
-                            eprintln!(
-                                "Second JSON parsing error: {}. Response was: {}",
-                                e2, response.response
-                            );
-                        }
-                        Vec::new()
+
+                let slot = chunk_owners.len();
+                chunk_owners.push(path.clone());
+                chunk_keys.push((path.clone(), chunk_index, content_hash.clone()));
+
+                match cache.get(path, chunk_index, &content_hash, &self.model) {
+                    Some(embedding) => chunk_embeddings.push(Some(embedding)),
+                    None => {
+                        chunk_embeddings.push(None);
+                        pending_indices.push(slot);
+                        pending_texts.push(chunk);
                     }
                 }
             }
-        };
+        }
 
-        // Match scores back to original filenames, defaulting to 0.0 for any missing scores
-        let result = files
-            .iter()
-            .map(|(_, name)| {
-                scores
-                    .iter()
-                    .find(|score| score.filename == *name)
-                    .map_or_else(|| 0.0, |score| score.score)
+        if chunk_owners.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        if self.verbose {
+            println!(
+                "Embedding {} of {} chunks from {} candidate files ({} served from cache)...",
+                pending_texts.len(),
+                chunk_owners.len(),
+                candidates.len(),
+                chunk_owners.len() - pending_texts.len()
+            );
+        }
+
+        // Dispatch up to `self.concurrency` batches to Ollama at once, instead
+        // of awaiting them one at a time. Each batch checks `cancel` right
+        // before dispatching, so an abort between batches skips any embedding
+        // requests that haven't gone out yet.
+        let batch_starts: Vec<usize> = (0..pending_texts.len()).step_by(BATCH_SIZE).collect();
+        let mut batch_results: Vec<(usize, Result<Vec<Vec<f32>>>)> = stream::iter(batch_starts)
+            .map(|batch_start| {
+                let batch_end = (batch_start + BATCH_SIZE).min(pending_texts.len());
+                let batch = &pending_texts[batch_start..batch_end];
+                async move {
+                    if cancel.is_cancelled() {
+                        return (batch_start, Ok(Vec::new()));
+                    }
+                    (batch_start, self.embed_batch_with_retry(batch).await)
+                }
             })
-            .collect();
+            .buffer_unordered(self.concurrency)
+            .collect()
+            .await;
+        batch_results.sort_by_key(|(batch_start, _)| *batch_start);
+
+        if cancel.is_cancelled() {
+            return Ok(Vec::new());
+        }
+
+        for (batch_start, result) in batch_results {
+            for (offset, embedding) in result?.into_iter().enumerate() {
+                let slot = pending_indices[batch_start + offset];
+                let (path, chunk_index, content_hash) = chunk_keys[slot].clone();
+                cache.put(
+                    path,
+                    chunk_index,
+                    content_hash,
+                    self.model.clone(),
+                    embedding.clone(),
+                );
+                chunk_embeddings[slot] = Some(embedding);
+            }
+        }
+
+        cache.flush()?;
+
+        let mut index = HnswIndex::new(HNSW_M, HNSW_EF_CONSTRUCTION);
+        for embedding in chunk_embeddings.into_iter().flatten() {
+            index.insert(embedding);
+        }
+
+        let query_embedding = self
+            .embed_batch_with_retry(std::slice::from_ref(&query.to_string()))
+            .await?
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("Ollama returned no embedding for the query"))?;
+
+        let k = chunk_owners.len().min(EMBEDDING_SEARCH_K);
+        let neighbors = index.search(&query_embedding, k, HNSW_EF_SEARCH);
+
+        // Keep each file's best-scoring chunk.
+        let mut best_scores: HashMap<PathBuf, f32> = HashMap::new();
+        for (chunk_id, score) in neighbors {
+            let path = chunk_owners[chunk_id].clone();
+            best_scores
+                .entry(path)
+                .and_modify(|existing| {
+                    if score > *existing {
+                        *existing = score;
+                    }
+                })
+                .or_insert(score);
+        }
 
-        Ok(result)
+        let mut scored_candidates: Vec<(PathBuf, f32)> = best_scores.into_iter().collect();
+        scored_candidates
+            .sort_by(|(_, a), (_, b)| b.partial_cmp(a).unwrap_or(std::cmp::Ordering::Equal));
+        Ok(scored_candidates)
     }
 
     pub async fn collect_and_sort_candidates(
         &self,
-        dir: &Path,
-        ignore_paths: &[&str],
+        dirs: &[PathBuf],
+        include_paths: &[&str],
+        exclude_paths: &[&str],
         query: &str,
+        cancel: &CancellationToken,
     ) -> Result<Vec<(PathBuf, f32)>> {
-        let candidates = self.collect_candidates(dir, ignore_paths).await?;
-
-        // Process candidates in batches
-        let mut scored_candidates = Vec::new();
-        for chunk in candidates.chunks(BATCH_SIZE) {
-            let batch: Vec<(PathBuf, String)> = chunk
-                .iter()
-                .map(|path| {
-                    let filename = path
-                        .file_name()
-                        .map(|f| f.to_string_lossy().to_string())
-                        .unwrap_or_default();
-                    (path.clone(), filename)
-                })
-                .collect();
+        if cancel.is_cancelled() {
+            return Ok(Vec::new());
+        }
 
-            let scores = self.analyze_filenames_batch(&batch, query).await?;
+        let mut candidates = self.collect_candidates(dirs, include_paths, exclude_paths).await?;
 
-            scored_candidates.extend(chunk.iter().cloned().zip(scores));
+        if let Some(top_n) = self.prefilter_top_n {
+            candidates = self.prefilter_with_bm25(candidates, query, top_n);
         }
 
-        scored_candidates
-            .sort_by(|(_, a), (_, b)| b.partial_cmp(a).unwrap_or(std::cmp::Ordering::Equal));
-        Ok(scored_candidates)
+        self.score_candidates_by_embedding(&candidates, query, cancel)
+            .await
     }
 
     pub async fn collect_sort_with_retry(
         &self,
-        dir: &Path,
-        ignore_paths: &[&str],
+        dirs: &[PathBuf],
+        include_paths: &[&str],
+        exclude_paths: &[&str],
         query: &str,
+        cancel: &CancellationToken,
     ) -> Result<Vec<(PathBuf, f32)>> {
         let mut try_count = 0;
         while try_count < MAX_SORT_TRY_COUNT {
             let candidates = self
-                .collect_and_sort_candidates(dir, ignore_paths, query)
+                .collect_and_sort_candidates(dirs, include_paths, exclude_paths, query, cancel)
                 .await?;
 
             if candidates.is_empty() {
@@ -182,41 +346,85 @@ Output: [{\"filename\":\"main.rs\",\"score\":0.3},{\"filename\":\"auth.rs\",\"sc
         non_ascii_count > BINARY_THRESHOLD
     }
 
-    fn should_ignore(&self, path: &Path, root: &Path, ignore_paths: &[&str]) -> bool {
-        // Get path relative to root
+    /// Whether `path` (a file, not a directory) should be dropped given the
+    /// include/exclude glob sets. Excludes always win; when an include set is
+    /// present a file must match at least one include glob to be kept.
+    fn should_ignore(
+        &self,
+        path: &Path,
+        root: &Path,
+        include: &Option<GlobSet>,
+        exclude: &GlobSet,
+    ) -> bool {
         let rel_path = path.strip_prefix(root).unwrap_or(path);
 
-        // Check if any component of the path matches ignore patterns
-        for ignore in ignore_paths {
-            let ignore_path = Path::new(ignore);
+        if exclude.is_match(rel_path) {
+            return true;
+        }
 
-            // Check if the relative path starts with the ignore pattern
-            if rel_path.starts_with(ignore_path) {
+        if let Some(include) = include {
+            if !include.is_match(rel_path) {
                 return true;
             }
         }
+
         false
     }
 
-    async fn collect_candidates(&self, dir: &Path, ignore_paths: &[&str]) -> Result<Vec<PathBuf>> {
+    async fn collect_candidates(
+        &self,
+        dirs: &[PathBuf],
+        include_paths: &[&str],
+        exclude_paths: &[&str],
+    ) -> Result<Vec<PathBuf>> {
+        let include = if include_paths.is_empty() {
+            None
+        } else {
+            Some(build_globset(include_paths)?)
+        };
+        let exclude = build_globset(exclude_paths)?;
+
+        let mut candidates = Vec::new();
+        for dir in dirs {
+            let mut sub_candidates = self.walk_dir(dir, dir, &include, &exclude).await?;
+            candidates.append(&mut sub_candidates);
+        }
+
+        Ok(candidates)
+    }
+
+    async fn walk_dir(
+        &self,
+        dir: &Path,
+        root: &Path,
+        include: &Option<GlobSet>,
+        exclude: &GlobSet,
+    ) -> Result<Vec<PathBuf>> {
         let mut candidates = Vec::new();
 
         for entry in std::fs::read_dir(dir)? {
             let entry = entry?;
             let path = entry.path();
 
-            // Skip if path matches ignore patterns
-            if self.should_ignore(&path, dir, ignore_paths) {
-                continue;
-            }
-
             if path.is_dir() {
+                // Directories are only pruned by excludes: an include glob like
+                // `src/**` must still let us descend into `src` to reach matches.
+                let rel_path = path.strip_prefix(root).unwrap_or(&path);
+                if exclude.is_match(rel_path) {
+                    continue;
+                }
+
                 let mut sub_candidates =
-                    Box::pin(self.collect_candidates(&path, ignore_paths)).await?;
+                    Box::pin(self.walk_dir(&path, root, include, exclude)).await?;
                 candidates.append(&mut sub_candidates);
                 continue;
             }
 
+            // Skip if path is excluded, or not included when include globs are given
+            if self.should_ignore(&path, root, include, exclude) {
+                continue;
+            }
+
             // Get metadata once and reuse
             let metadata = match entry.metadata() {
                 Ok(m) => m,
@@ -250,3 +458,43 @@ Output: [{\"filename\":\"main.rs\",\"score\":0.3},{\"filename\":\"auth.rs\",\"sc
         Ok(candidates)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    /// Nested `target`/`node_modules` directories (e.g. `crate-b/target`,
+    /// `packages/foo/node_modules`) must be excluded the same as top-level
+    /// ones, not just when they sit directly under the search root.
+    #[tokio::test]
+    async fn ignore_paths_exclude_nested_directories() {
+        let root = std::env::temp_dir().join(format!(
+            "llmgrep-test-{}-{}",
+            std::process::id(),
+            "ignore-nested"
+        ));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("crate-b/target")).unwrap();
+        fs::write(root.join("crate-b/target/built.rs"), "fn built() {}").unwrap();
+        fs::create_dir_all(root.join("packages/foo/node_modules")).unwrap();
+        fs::write(
+            root.join("packages/foo/node_modules/index.js"),
+            "module.exports = {}",
+        )
+        .unwrap();
+        fs::create_dir_all(root.join("packages/foo/src")).unwrap();
+        fs::write(root.join("packages/foo/src/main.rs"), "fn main() {}").unwrap();
+
+        let sorter = LlmSort::new("test-model", false, None, 1).await.unwrap();
+        let exclude_paths = ["target", "node_modules"];
+        let candidates = sorter
+            .collect_candidates(&[root.clone()], &[], &exclude_paths)
+            .await
+            .unwrap();
+
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(candidates, vec![root.join("packages/foo/src/main.rs")]);
+    }
+}
@@ -1,9 +1,15 @@
+mod bm25;
+mod cache;
+mod chunk;
+mod hnsw;
+mod interactive;
 mod llmgrep;
 mod llmsort;
 use anyhow::Result;
 use clap::Parser;
-use llmgrep::LlmGrep;
+use llmgrep::{LlmGrep, OutputFormat, SearchOptions};
 use std::path::PathBuf;
+use tokio_util::sync::CancellationToken;
 
 /// Semantic code search using local LLMs
 #[derive(Parser)]
@@ -12,14 +18,22 @@ struct Args {
     /// Search query - what to look for semantically
     query: String,
 
-    /// Directory to search in
+    /// Directories to search in (can be passed multiple times)
     #[arg(default_value = ".")]
-    directory: PathBuf,
+    directory: Vec<PathBuf>,
 
     /// LLM model to use (default: dolphin-mistral:latest)
     #[arg(long, default_value = "dolphin-mistral:latest")]
     model: String,
 
+    /// Glob patterns a file must match to be considered, e.g. `--include '*.rs' --include 'src/**'`
+    #[arg(long = "include")]
+    include: Vec<String>,
+
+    /// Glob patterns to exclude in addition to `ignore_paths`, e.g. `--exclude '**/*.min.js'`
+    #[arg(long = "exclude")]
+    exclude: Vec<String>,
+
     /// Paths to ignore during search (comma separated)
     #[arg(
         long,
@@ -31,6 +45,36 @@ struct Args {
     /// Enable verbose output
     #[arg(short, long)]
     verbose: bool,
+
+    /// Output format: human-readable text, or one JSON match per line
+    #[arg(long, value_enum, default_value = "text")]
+    format: OutputFormat,
+
+    /// Lexical pre-ranking to run before embedding/LLM scoring, to shrink the candidate set
+    #[arg(long, value_enum)]
+    prefilter: Option<Prefilter>,
+
+    /// Max candidates kept by `--prefilter`
+    #[arg(long, default_value_t = 50)]
+    prefilter_top_n: usize,
+
+    /// Narrow the sorted candidates down interactively (via `fzf` if installed)
+    /// before running the expensive content analysis pass
+    #[arg(long)]
+    interactive: bool,
+
+    /// With `--interactive`, print only the filename score of each selected file
+    #[arg(long, requires = "interactive")]
+    print_score_only: bool,
+
+    /// Max number of embedding/content-analysis requests dispatched to Ollama at once
+    #[arg(long, default_value_t = 4)]
+    concurrency: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum Prefilter {
+    Bm25,
 }
 
 #[tokio::main]
@@ -40,15 +84,45 @@ async fn main() -> Result<()> {
     if args.verbose {
         println!("Initializing LLM Grep with local Ollama model...");
     }
-    let llm_grep = LlmGrep::new(&args.model, args.verbose).await?;
+    let prefilter_top_n = args.prefilter.map(|_| args.prefilter_top_n);
+    let llm_grep = LlmGrep::new(&args.model, args.verbose, prefilter_top_n, args.concurrency).await?;
 
-    let ignore_paths: Vec<&str> = args.ignore_paths.iter().map(|s| s.as_str()).collect();
+    let include_paths: Vec<&str> = args.include.iter().map(|s| s.as_str()).collect();
+    let exclude_paths: Vec<&str> = args
+        .ignore_paths
+        .iter()
+        .chain(args.exclude.iter())
+        .map(|s| s.as_str())
+        .collect();
 
     if args.verbose {
         println!("Searching for: {}", args.query);
     }
+
+    // Let Ctrl+C cancel an in-flight search cleanly instead of killing the process mid-batch.
+    let cancel = CancellationToken::new();
+    let cancel_on_signal = cancel.clone();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            cancel_on_signal.cancel();
+        }
+    });
+
+    let options = SearchOptions {
+        format: args.format,
+        interactive: args.interactive,
+        print_score_only: args.print_score_only,
+    };
+
     llm_grep
-        .search_directory(&args.directory, &ignore_paths, &args.query)
+        .search_directory(
+            &args.directory,
+            &include_paths,
+            &exclude_paths,
+            &args.query,
+            options,
+            cancel,
+        )
         .await?;
 
     Ok(())